@@ -0,0 +1,48 @@
+use rusqlite::{params, Connection};
+
+/// Identifies a private one-to-one dialog independent of participant order:
+/// `DialogId::new(a, b) == DialogId::new(b, a)`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DialogId(String);
+
+impl DialogId {
+    pub fn new(user_a: &str, user_b: &str) -> Self {
+        let (first, second) = if user_a <= user_b {
+            (user_a, user_b)
+        } else {
+            (user_b, user_a)
+        };
+        DialogId(format!("{first}\u{1}{second}"))
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+/// Look up (creating if needed) the `dialogs` row between `user_a` and
+/// `user_b`, returning its id.
+pub fn ensure_dialog(conn: &Connection, user_a: &str, user_b: &str) -> i64 {
+    let id = DialogId::new(user_a, user_b);
+    conn.execute(
+        "INSERT OR IGNORE INTO dialogs (dialog_key) VALUES (?1)",
+        params![id.as_str()],
+    )
+    .unwrap();
+    conn.query_row(
+        "SELECT id FROM dialogs WHERE dialog_key=?1",
+        params![id.as_str()],
+        |row| row.get(0),
+    )
+    .unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::DialogId;
+
+    #[test]
+    fn dialog_id_is_order_invariant() {
+        assert_eq!(DialogId::new("alice", "bob"), DialogId::new("bob", "alice"));
+    }
+}
@@ -0,0 +1,117 @@
+use rusqlite::{params, Connection, Row};
+
+/// One row of chat history: username, message content, ISO 8601
+/// (`YYYY-MM-DDTHH:MM:SS`) timestamp, and row id.
+pub type HistoryRow = (String, String, String, i64);
+
+/// Outcome of a history query, distinguishing "ran fine but nothing found"
+/// from "the parameters didn't make sense" so callers can render each case.
+pub enum HistoryResult {
+    Messages(Vec<HistoryRow>),
+    Empty,
+    InvalidParams,
+}
+
+/// Which way to page from `reference`.
+pub enum Direction {
+    /// The most recent `limit` messages, newest first.
+    Latest,
+    /// Up to `limit` messages strictly older than `reference`, newest first.
+    Before,
+    /// Up to `limit` messages strictly newer than `reference`, oldest first.
+    After,
+}
+
+/// Query a page of chat history for `room`.
+///
+/// `reference_ts` is the ISO timestamp to page from; it's required for
+/// `Before`/`After` and ignored for `Latest`. Since `ts` only has
+/// second resolution, messages sharing a timestamp are disambiguated with
+/// `reference_id`, compared as a `(ts, id)` pair; when the caller doesn't
+/// have an id to anchor on (e.g. a hand-typed SSH timestamp), it's left
+/// `None` and we default to the permissive end of the id range so the whole
+/// referenced second is included rather than silently dropped. Returns
+/// `InvalidParams` if `limit` isn't positive or a required `reference_ts` is
+/// missing.
+pub fn query(
+    conn: &Connection,
+    room: &str,
+    direction: Direction,
+    reference_ts: Option<&str>,
+    reference_id: Option<i64>,
+    limit: i64,
+) -> HistoryResult {
+    if limit <= 0 {
+        return HistoryResult::InvalidParams;
+    }
+
+    let rows = match direction {
+        Direction::Latest => {
+            let mut stmt = conn
+                .prepare(
+                    "SELECT users.username, messages.content, strftime('%Y-%m-%dT%H:%M:%S', messages.ts), messages.id \
+                     FROM messages \
+                     JOIN users ON users.id = messages.user_id \
+                     JOIN rooms ON rooms.id = messages.room_id \
+                     WHERE rooms.name = ?1 ORDER BY messages.ts DESC, messages.id DESC LIMIT ?2",
+                )
+                .unwrap();
+            let mapped = stmt.query_map(params![room, limit], row_to_tuple).unwrap();
+            mapped.map(|r| r.unwrap()).collect::<Vec<_>>()
+        }
+        Direction::Before => {
+            let Some(ts) = reference_ts else {
+                return HistoryResult::InvalidParams;
+            };
+            // No id to anchor on means "include the whole referenced second".
+            let id = reference_id.unwrap_or(i64::MAX);
+            let mut stmt = conn
+                .prepare(
+                    "SELECT users.username, messages.content, strftime('%Y-%m-%dT%H:%M:%S', messages.ts), messages.id \
+                     FROM messages \
+                     JOIN users ON users.id = messages.user_id \
+                     JOIN rooms ON rooms.id = messages.room_id \
+                     WHERE rooms.name = ?1 \
+                       AND (strftime('%Y-%m-%dT%H:%M:%S', messages.ts), messages.id) < (?2, ?3) \
+                     ORDER BY messages.ts DESC, messages.id DESC LIMIT ?4",
+                )
+                .unwrap();
+            let mapped = stmt
+                .query_map(params![room, ts, id, limit], row_to_tuple)
+                .unwrap();
+            mapped.map(|r| r.unwrap()).collect::<Vec<_>>()
+        }
+        Direction::After => {
+            let Some(ts) = reference_ts else {
+                return HistoryResult::InvalidParams;
+            };
+            // No id to anchor on means "include the whole referenced second".
+            let id = reference_id.unwrap_or(i64::MIN);
+            let mut stmt = conn
+                .prepare(
+                    "SELECT users.username, messages.content, strftime('%Y-%m-%dT%H:%M:%S', messages.ts), messages.id \
+                     FROM messages \
+                     JOIN users ON users.id = messages.user_id \
+                     JOIN rooms ON rooms.id = messages.room_id \
+                     WHERE rooms.name = ?1 \
+                       AND (strftime('%Y-%m-%dT%H:%M:%S', messages.ts), messages.id) > (?2, ?3) \
+                     ORDER BY messages.ts ASC, messages.id ASC LIMIT ?4",
+                )
+                .unwrap();
+            let mapped = stmt
+                .query_map(params![room, ts, id, limit], row_to_tuple)
+                .unwrap();
+            mapped.map(|r| r.unwrap()).collect::<Vec<_>>()
+        }
+    };
+
+    if rows.is_empty() {
+        HistoryResult::Empty
+    } else {
+        HistoryResult::Messages(rows)
+    }
+}
+
+fn row_to_tuple(row: &Row) -> rusqlite::Result<HistoryRow> {
+    Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?))
+}
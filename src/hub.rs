@@ -0,0 +1,157 @@
+use russh::server::Handle;
+use russh::{ChannelId, CryptoVec};
+use std::collections::HashMap;
+use std::sync::Arc;
+use tokio::io::AsyncWriteExt;
+use tokio::net::tcp::OwnedWriteHalf;
+use tokio::sync::Mutex as AsyncMutex;
+
+use crate::recorder::Recorder;
+use crate::rooms::RoomRegistry;
+
+/// Where a broadcast is actually written, one variant per connected front end.
+pub enum ClientSink {
+    Ssh {
+        channel: ChannelId,
+        handle: Handle,
+        /// Set when this session is being recorded (see `recorder::Recorder`).
+        recorder: Option<Arc<Recorder>>,
+    },
+    Irc {
+        writer: Arc<AsyncMutex<OwnedWriteHalf>>,
+    },
+}
+
+impl ClientSink {
+    /// Deliver a chat message from `sender` in `room`, formatted for this
+    /// client's own transport.
+    async fn notify_message(&self, room: &str, sender: &str, content: &str) {
+        match self {
+            ClientSink::Ssh {
+                channel,
+                handle,
+                recorder,
+            } => {
+                let text = format!("\r\n{}: {}\r\n> ", sender, content);
+                if let Some(recorder) = recorder {
+                    recorder.record_output(&text);
+                }
+                let _ = handle.data(*channel, CryptoVec::from(text)).await;
+            }
+            ClientSink::Irc { writer } => {
+                let line =
+                    format!(":{sender}!{sender}@nojs-chat PRIVMSG #{room} :{content}\r\n");
+                let _ = writer.lock().await.write_all(line.as_bytes()).await;
+            }
+        }
+    }
+
+    /// Deliver a private message from `sender`, addressed to `recipient`
+    /// (this client's own username), regardless of which room either party
+    /// is currently in.
+    async fn notify_dm(&self, recipient: &str, sender: &str, content: &str) {
+        match self {
+            ClientSink::Ssh {
+                channel,
+                handle,
+                recorder,
+            } => {
+                let text = format!("\r\n[DM from {}] {}\r\n> ", sender, content);
+                if let Some(recorder) = recorder {
+                    recorder.record_output(&text);
+                }
+                let _ = handle.data(*channel, CryptoVec::from(text)).await;
+            }
+            ClientSink::Irc { writer } => {
+                let line = format!(":{sender}!{sender}@nojs-chat PRIVMSG {recipient} :{content}\r\n");
+                let _ = writer.lock().await.write_all(line.as_bytes()).await;
+            }
+        }
+    }
+
+    /// Deliver a system notice (join/part/etc) for `room` to this client.
+    async fn notify_system(&self, room: &str, text: &str) {
+        match self {
+            ClientSink::Ssh {
+                channel,
+                handle,
+                recorder,
+            } => {
+                let out = format!("\r\n{}\r\n> ", text);
+                if let Some(recorder) = recorder {
+                    recorder.record_output(&out);
+                }
+                let _ = handle.data(*channel, CryptoVec::from(out)).await;
+            }
+            ClientSink::Irc { writer } => {
+                let line = format!(":nojs-chat NOTICE #{room} :{text}\r\n");
+                let _ = writer.lock().await.write_all(line.as_bytes()).await;
+            }
+        }
+    }
+}
+
+/// A single connected client, regardless of which front end it came in on.
+pub struct RegisteredClient {
+    pub username: String,
+    pub sink: ClientSink,
+}
+
+pub type ClientRegistry = HashMap<usize, RegisteredClient>;
+
+/// Fan a chat message out to every client currently joined to `room`.
+pub async fn broadcast_message(
+    rooms: &AsyncMutex<RoomRegistry>,
+    clients: &AsyncMutex<ClientRegistry>,
+    room: &str,
+    sender: &str,
+    content: &str,
+) {
+    let registry = rooms.lock().await;
+    let Some(state) = registry.get(room) else {
+        return;
+    };
+    let clients = clients.lock().await;
+    for id in &state.members {
+        if let Some(client) = clients.get(id) {
+            client.sink.notify_message(room, sender, content).await;
+        }
+    }
+}
+
+/// Deliver a private message to `target_username` if they're currently
+/// connected (on any front end), returning whether anyone received it.
+pub async fn deliver_dm(
+    clients: &AsyncMutex<ClientRegistry>,
+    target_username: &str,
+    sender: &str,
+    content: &str,
+) -> bool {
+    let clients = clients.lock().await;
+    for client in clients.values() {
+        if client.username == target_username {
+            client.sink.notify_dm(&client.username, sender, content).await;
+            return true;
+        }
+    }
+    false
+}
+
+/// Fan a system notice (join/part) out to every client currently joined to `room`.
+pub async fn broadcast_system(
+    rooms: &AsyncMutex<RoomRegistry>,
+    clients: &AsyncMutex<ClientRegistry>,
+    room: &str,
+    text: &str,
+) {
+    let registry = rooms.lock().await;
+    let Some(state) = registry.get(room) else {
+        return;
+    };
+    let clients = clients.lock().await;
+    for id in &state.members {
+        if let Some(client) = clients.get(id) {
+            client.sink.notify_system(room, text).await;
+        }
+    }
+}
@@ -0,0 +1,315 @@
+use actix_web::web;
+use rusqlite::params;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::Mutex as AsyncMutex;
+
+use crate::hub::{self, ClientSink, RegisteredClient};
+use crate::rooms;
+use crate::{check_and_upgrade_password, AppState};
+
+const SERVER_NAME: &str = "nojs-chat";
+
+/// Accept IRC connections forever, servicing each on its own task.
+pub async fn run(data: web::Data<AppState>, port: u16) {
+    let listener = match TcpListener::bind(("0.0.0.0", port)).await {
+        Ok(l) => l,
+        Err(e) => {
+            eprintln!("IRC listener failed to bind port {}: {}", port, e);
+            return;
+        }
+    };
+    loop {
+        match listener.accept().await {
+            Ok((stream, _)) => {
+                let data = data.clone();
+                tokio::spawn(async move {
+                    if let Err(e) = handle_connection(stream, data).await {
+                        eprintln!("IRC session error: {:?}", e);
+                    }
+                });
+            }
+            Err(e) => eprintln!("IRC accept error: {}", e),
+        }
+    }
+}
+
+struct IrcSession {
+    id: usize,
+    nick: Option<String>,
+    user_sent: bool,
+    pass: Option<String>,
+    registered: bool,
+    room: Option<String>,
+    writer: Arc<AsyncMutex<tokio::net::tcp::OwnedWriteHalf>>,
+}
+
+impl IrcSession {
+    async fn send(&self, line: &str) {
+        let mut w = self.writer.lock().await;
+        let _ = w.write_all(line.as_bytes()).await;
+        let _ = w.write_all(b"\r\n").await;
+    }
+
+    async fn numeric(&self, code: &str, rest: &str) {
+        let nick = self.nick.as_deref().unwrap_or("*");
+        self.send(&format!(":{} {} {} {}", SERVER_NAME, code, nick, rest))
+            .await;
+    }
+}
+
+async fn handle_connection(stream: TcpStream, data: web::Data<AppState>) -> std::io::Result<()> {
+    let (read_half, write_half) = stream.into_split();
+    let mut lines = BufReader::new(read_half).lines();
+    let writer = Arc::new(AsyncMutex::new(write_half));
+    let id = data.next_client_id.fetch_add(1, Ordering::Relaxed);
+
+    let mut session = IrcSession {
+        id,
+        nick: None,
+        user_sent: false,
+        pass: None,
+        registered: false,
+        room: None,
+        writer,
+    };
+
+    while let Some(line) = lines.next_line().await? {
+        let line = line.trim_end();
+        if line.is_empty() {
+            continue;
+        }
+        if !dispatch(&mut session, line, &data).await {
+            break;
+        }
+    }
+
+    if let Some(room) = session.room.take() {
+        let mut registry = data.rooms.lock().await;
+        if let Some(state) = registry.get_mut(&room) {
+            state.members.remove(&session.id);
+        }
+        drop(registry);
+        if let Some(nick) = &session.nick {
+            hub::broadcast_system(
+                &data.rooms,
+                &data.clients,
+                &room,
+                &format!("* {} left #{}", nick, room),
+            )
+            .await;
+        }
+    }
+    data.clients.lock().await.remove(&session.id);
+    Ok(())
+}
+
+/// Handle a single IRC protocol line. Returns `false` when the connection should close.
+async fn dispatch(session: &mut IrcSession, line: &str, data: &web::Data<AppState>) -> bool {
+    let mut parts = line.splitn(2, ' ');
+    let command = parts.next().unwrap_or("").to_uppercase();
+    let rest = parts.next().unwrap_or("");
+
+    match command.as_str() {
+        "PASS" => {
+            session.pass = Some(rest.trim().trim_start_matches(':').to_string());
+        }
+        "NICK" => {
+            session.nick = Some(rest.trim().trim_start_matches(':').to_string());
+            try_register(session, data).await;
+        }
+        "USER" => {
+            session.user_sent = true;
+            try_register(session, data).await;
+        }
+        "JOIN" => {
+            if !session.registered {
+                return true;
+            }
+            let target = rooms::normalize_room_name(rest.trim());
+            if !target.is_empty() {
+                join_room(session, data, &target).await;
+            }
+        }
+        "PART" => part_room(session, data).await,
+        "PRIVMSG" => privmsg(session, data, rest).await,
+        "PING" => {
+            session
+                .send(&format!(":{} PONG {} :{}", SERVER_NAME, SERVER_NAME, rest))
+                .await;
+        }
+        "QUIT" => return false,
+        _ => {}
+    }
+    true
+}
+
+async fn try_register(session: &mut IrcSession, data: &web::Data<AppState>) {
+    if session.registered || session.nick.is_none() || !session.user_sent {
+        return;
+    }
+    let nick = session.nick.clone().unwrap();
+    let password = session.pass.clone().unwrap_or_default();
+
+    let authenticated = {
+        let conn = data.conn.lock().unwrap();
+        let row: Option<(i64, String)> = conn
+            .query_row(
+                "SELECT id, password FROM users WHERE username=?1",
+                params![nick],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .ok();
+        match row {
+            Some((id, stored)) => check_and_upgrade_password(&conn, id, &stored, &password),
+            None => false,
+        }
+    };
+
+    if !authenticated {
+        session.numeric("464", ":Password incorrect").await;
+        return;
+    }
+
+    session.registered = true;
+    data.clients.lock().await.insert(
+        session.id,
+        RegisteredClient {
+            username: nick.clone(),
+            sink: ClientSink::Irc {
+                writer: session.writer.clone(),
+            },
+        },
+    );
+
+    session
+        .numeric("001", &format!(":Welcome to {}, {}", SERVER_NAME, nick))
+        .await;
+    session
+        .numeric("002", &format!(":Your host is {}", SERVER_NAME))
+        .await;
+    session
+        .numeric("003", ":This server was started earlier today")
+        .await;
+    session
+        .numeric("004", &format!("{} nojs-chat-1 o o", SERVER_NAME))
+        .await;
+}
+
+async fn join_room(session: &mut IrcSession, data: &web::Data<AppState>, target: &str) {
+    {
+        let conn = data.conn.lock().unwrap();
+        rooms::ensure_room(&conn, target);
+    }
+    let previous = session.room.take();
+    {
+        let mut registry = data.rooms.lock().await;
+        rooms::move_client(&mut registry, session.id, previous.as_deref(), target);
+    }
+    session.room = Some(target.to_string());
+    let nick = session.nick.clone().unwrap_or_default();
+
+    session
+        .send(&format!(":{nick}!{nick}@{SERVER_NAME} JOIN #{target}"))
+        .await;
+    session
+        .numeric("332", &format!("#{} :Welcome to #{}", target, target))
+        .await;
+
+    let names = {
+        // Lock `rooms` before `clients`, matching `hub::broadcast_*`, so the
+        // two process-wide mutexes are never taken in opposite orders.
+        let registry = data.rooms.lock().await;
+        let clients = data.clients.lock().await;
+        let mut names = Vec::new();
+        if let Some(state) = registry.get(target) {
+            for id in &state.members {
+                if let Some(c) = clients.get(id) {
+                    names.push(c.username.clone());
+                }
+            }
+        }
+        names
+    };
+    session
+        .numeric("353", &format!("= #{} :{}", target, names.join(" ")))
+        .await;
+    session
+        .numeric("366", &format!("#{} :End of /NAMES list", target))
+        .await;
+
+    hub::broadcast_system(
+        &data.rooms,
+        &data.clients,
+        target,
+        &format!("* {} joined #{}", nick, target),
+    )
+    .await;
+}
+
+async fn part_room(session: &mut IrcSession, data: &web::Data<AppState>) {
+    let Some(room) = session.room.clone() else {
+        return;
+    };
+    if room == rooms::DEFAULT_ROOM {
+        return;
+    }
+    {
+        let mut registry = data.rooms.lock().await;
+        rooms::move_client(&mut registry, session.id, Some(&room), rooms::DEFAULT_ROOM);
+    }
+    let nick = session.nick.clone().unwrap_or_default();
+    hub::broadcast_system(
+        &data.rooms,
+        &data.clients,
+        &room,
+        &format!("* {} left #{}", nick, room),
+    )
+    .await;
+    session
+        .send(&format!(":{nick}!{nick}@{SERVER_NAME} PART #{room} :Leaving"))
+        .await;
+    session.room = Some(rooms::DEFAULT_ROOM.to_string());
+}
+
+async fn privmsg(session: &mut IrcSession, data: &web::Data<AppState>, rest: &str) {
+    if !session.registered {
+        return;
+    }
+    let mut msg_parts = rest.splitn(2, " :");
+    let target = msg_parts
+        .next()
+        .unwrap_or("")
+        .trim()
+        .trim_start_matches('#')
+        .to_string();
+    let content = msg_parts.next().unwrap_or("").to_string();
+    if content.is_empty() {
+        return;
+    }
+    if session.room.as_deref() != Some(target.as_str()) {
+        session
+            .numeric("442", &format!("#{} :You're not on that channel", target))
+            .await;
+        return;
+    }
+
+    let nick = session.nick.clone().unwrap_or_default();
+    {
+        let conn = data.conn.lock().unwrap();
+        let room_id = rooms::ensure_room(&conn, &target);
+        if let Ok(uid) =
+            conn.query_row("SELECT id FROM users WHERE username=?1", params![nick], |row| {
+                row.get::<_, i64>(0)
+            })
+        {
+            let _ = conn.execute(
+                "INSERT INTO messages (user_id, room_id, content) VALUES (?1, ?2, ?3)",
+                params![uid, room_id, content],
+            );
+        }
+    }
+    hub::broadcast_message(&data.rooms, &data.clients, &target, &nick, &content).await;
+}
@@ -1,7 +1,11 @@
 use actix_web::cookie::{time::Duration, Cookie};
 use actix_web::{web, App, HttpRequest, HttpResponse, HttpServer, Responder};
+use argon2::password_hash::rand_core::OsRng;
+use argon2::password_hash::{PasswordHash, PasswordHasher, PasswordVerifier, SaltString};
+use argon2::Argon2;
 use askama::Template;
 use clap::Parser;
+use rand_core::RngCore;
 use rusqlite::{params, Connection};
 use russh::server::{Auth, Msg, Server as _, Session};
 use russh::{server, Channel, ChannelId, CryptoVec};
@@ -9,14 +13,26 @@ use serde::Deserialize;
 use serde::Serialize;
 use serde_yaml;
 use std::collections::HashMap;
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::{Arc, Mutex};
 use tokio::sync::Mutex as AsyncMutex;
 
+mod dialogs;
+mod history;
+mod hub;
+mod irc;
+mod pubkey;
+mod recorder;
+mod rooms;
+
 #[derive(Serialize, Deserialize, Clone)]
 struct Config {
     http_port: u16,
     ssh_port: u16,
+    irc_port: u16,
     chat_name: String,
+    recording_enabled: bool,
+    recordings_dir: String,
 }
 
 impl Default for Config {
@@ -24,13 +40,16 @@ impl Default for Config {
         Self {
             http_port: 8080,
             ssh_port: 2222,
+            irc_port: 6667,
             chat_name: "NoJS Chat".to_string(),
+            recording_enabled: false,
+            recordings_dir: "recordings".to_string(),
         }
     }
 }
 
 #[derive(Parser)]
-#[command(name = "nojs-chat", about = "Minimal chat server over HTTP and SSH")]
+#[command(name = "nojs-chat", about = "Minimal chat server over HTTP, SSH and IRC")]
 struct Args {
     /// HTTP port
     #[arg(short = 'p', long = "port")]
@@ -40,10 +59,22 @@ struct Args {
     #[arg(short = 's', long = "ssh")]
     ssh_port: Option<u16>,
 
+    /// IRC port
+    #[arg(short = 'i', long = "irc")]
+    irc_port: Option<u16>,
+
     /// Chat name
     #[arg(short = 'n', long = "name")]
     chat_name: Option<String>,
 
+    /// Record SSH sessions as asciicast v2 files
+    #[arg(long = "record", action = clap::ArgAction::SetTrue)]
+    record: bool,
+
+    /// Directory to store SSH session recordings in
+    #[arg(long = "recordings-dir")]
+    recordings_dir: Option<String>,
+
     /// Path to config file
     #[arg(short = 'c', long = "config", default_value = "config.yml")]
     config: String,
@@ -52,6 +83,87 @@ struct Args {
 struct AppState {
     conn: Mutex<Connection>,
     config: Config,
+    clients: AsyncMutex<hub::ClientRegistry>,
+    rooms: AsyncMutex<rooms::RoomRegistry>,
+    next_client_id: AtomicUsize,
+}
+
+/// Hash a cleartext password into a PHC-formatted Argon2id string.
+fn hash_password(password: &str) -> String {
+    let salt = SaltString::generate(&mut OsRng);
+    Argon2::default()
+        .hash_password(password.as_bytes(), &salt)
+        .expect("hash password")
+        .to_string()
+}
+
+/// Verify `password` against a stored PHC hash, constant-time.
+fn verify_password(password: &str, stored_hash: &str) -> bool {
+    match PasswordHash::new(stored_hash) {
+        Ok(parsed) => Argon2::default()
+            .verify_password(password.as_bytes(), &parsed)
+            .is_ok(),
+        Err(_) => false,
+    }
+}
+
+/// Check a user's password against the stored column, transparently
+/// re-hashing legacy cleartext rows (pre-Argon2 `chat.db` files) on success.
+fn check_and_upgrade_password(conn: &Connection, user_id: i64, stored: &str, attempt: &str) -> bool {
+    if PasswordHash::new(stored).is_ok() {
+        return verify_password(attempt, stored);
+    }
+    // Not a PHC string: this row predates Argon2 hashing, treat it as cleartext.
+    if stored == attempt {
+        let upgraded = hash_password(attempt);
+        let _ = conn.execute(
+            "UPDATE users SET password=?1 WHERE id=?2",
+            params![upgraded, user_id],
+        );
+        true
+    } else {
+        false
+    }
+}
+
+/// Generate a random opaque session token, hex-encoded.
+fn generate_session_token() -> String {
+    let mut bytes = [0u8; 32];
+    rand_core::OsRng.fill_bytes(&mut bytes);
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// Start a new session for `user_id`, recording it server-side, and return
+/// the opaque token to hand back as the `session_token` cookie.
+fn create_session(conn: &Connection, user_id: i64) -> String {
+    let token = generate_session_token();
+    conn.execute(
+        "INSERT INTO sessions (token, user_id) VALUES (?1, ?2)",
+        params![token, user_id],
+    )
+    .unwrap();
+    token
+}
+
+/// Whether `username` has a registered account.
+fn user_exists(conn: &Connection, username: &str) -> bool {
+    conn.query_row(
+        "SELECT 1 FROM users WHERE username=?1",
+        params![username],
+        |row| row.get::<_, i64>(0),
+    )
+    .is_ok()
+}
+
+/// Whether `user_id` is flagged as an admin (currently: whoever registered first).
+fn is_admin(conn: &Connection, user_id: i64) -> bool {
+    conn.query_row(
+        "SELECT is_admin FROM users WHERE id=?1",
+        params![user_id],
+        |row| row.get::<_, i64>(0),
+    )
+    .map(|flag| flag != 0)
+    .unwrap_or(false)
 }
 
 #[derive(Template)]
@@ -69,24 +181,83 @@ struct RegisterTemplate<'a> {
 struct ChatMessage {
     username: String,
     content: String,
+    ts: String,
+    id: i64,
 }
 
 #[derive(Clone)]
 struct SshServer {
     data: web::Data<AppState>,
-    clients: Arc<AsyncMutex<HashMap<usize, (String, ChannelId, russh::server::Handle)>>>,
     id: usize,
     username: Option<String>,
+    room: String,
+    recorder: Option<Arc<recorder::Recorder>>,
 }
 
 impl SshServer {
-    async fn broadcast(&self, msg: &str) {
-        let mut clients = self.clients.lock().await;
-        let data = CryptoVec::from(format!("\r\n{}\r\n> ", msg));
-        for (_, (_, channel, handle)) in clients.iter_mut() {
-            let _ = handle.data(*channel, data.clone()).await;
+    /// Write a line (plus a fresh prompt) directly back to this connection.
+    async fn send_to_self(&self, text: &str) {
+        let clients = self.data.clients.lock().await;
+        if let Some(client) = clients.get(&self.id) {
+            if let hub::ClientSink::Ssh {
+                channel,
+                handle,
+                recorder,
+            } = &client.sink
+            {
+                let out = format!("{}\r\n> ", text);
+                if let Some(recorder) = recorder {
+                    recorder.record_output(&out);
+                }
+                let _ = handle.data(*channel, CryptoVec::from(out)).await;
+            }
         }
     }
+
+    /// Accept `public_key` for `user` if it matches a row in `user_keys`,
+    /// setting `self.username` on success. Shared by the offered-key probe
+    /// and the real, signature-verified attempt.
+    fn auth_key_lookup(&mut self, user: &str, public_key: &russh::keys::PublicKey) -> Result<Auth, russh::Error> {
+        let Some((algorithm, blob)) = pubkey::encode_public_key(public_key) else {
+            return Ok(Auth::Reject {
+                proceed_with_methods: None,
+                partial_success: false,
+            });
+        };
+        let conn = self.data.conn.lock().unwrap();
+        let known = conn
+            .query_row(
+                "SELECT user_keys.id FROM user_keys \
+                 JOIN users ON users.id = user_keys.user_id \
+                 WHERE users.username=?1 AND user_keys.algorithm=?2 AND user_keys.key_blob=?3",
+                params![user, algorithm, blob],
+                |row| row.get::<_, i64>(0),
+            )
+            .is_ok();
+        if known {
+            self.username = Some(user.to_string());
+            Ok(Auth::Accept)
+        } else {
+            Ok(Auth::Reject {
+                proceed_with_methods: None,
+                partial_success: false,
+            })
+        }
+    }
+
+    /// Write `text` to the client through `session`, also recording it if
+    /// this session has an active recorder.
+    fn write_and_record(
+        &self,
+        channel: ChannelId,
+        session: &mut Session,
+        text: &str,
+    ) -> Result<(), russh::Error> {
+        if let Some(recorder) = &self.recorder {
+            recorder.record_output(text);
+        }
+        session.data(channel, CryptoVec::from(text))
+    }
 }
 
 impl server::Server for SshServer {
@@ -94,8 +265,7 @@ impl server::Server for SshServer {
 
     fn new_client(&mut self, _: Option<std::net::SocketAddr>) -> Self {
         let mut new = self.clone();
-        new.id = self.id + 1;
-        self.id += 1;
+        new.id = self.data.next_client_id.fetch_add(1, Ordering::Relaxed);
         new
     }
 
@@ -110,12 +280,16 @@ impl server::Handler for SshServer {
     async fn auth_password(&mut self, user: &str, password: &str) -> Result<Auth, Self::Error> {
         let conn = self.data.conn.lock().unwrap();
         let mut stmt = conn
-            .prepare("SELECT id FROM users WHERE username=?1 AND password=?2")
+            .prepare("SELECT id, password FROM users WHERE username=?1")
             .unwrap();
-        let ok: Option<i64> = stmt
-            .query_row(params![user, password], |row| row.get(0))
+        let row: Option<(i64, String)> = stmt
+            .query_row(params![user], |row| Ok((row.get(0)?, row.get(1)?)))
             .ok();
-        if ok.is_some() {
+        let authenticated = match row {
+            Some((id, stored)) => check_and_upgrade_password(&conn, id, &stored, password),
+            None => false,
+        };
+        if authenticated {
             self.username = Some(user.to_string());
             Ok(Auth::Accept)
         } else {
@@ -126,59 +300,87 @@ impl server::Handler for SshServer {
         }
     }
 
+    async fn auth_publickey_offered(
+        &mut self,
+        user: &str,
+        public_key: &russh::keys::PublicKey,
+    ) -> Result<Auth, Self::Error> {
+        // The client is only probing whether this key would be accepted; no
+        // signature has been produced yet, so just check it's registered.
+        self.auth_key_lookup(user, public_key)
+    }
+
+    async fn auth_publickey(
+        &mut self,
+        user: &str,
+        public_key: &russh::keys::PublicKey,
+    ) -> Result<Auth, Self::Error> {
+        // By the time this is called russh has already verified the
+        // signature over the session id, so a matching lookup is enough.
+        self.auth_key_lookup(user, public_key)
+    }
+
     async fn channel_open_session(
         &mut self,
         channel: Channel<Msg>,
         session: &mut Session,
     ) -> Result<bool, Self::Error> {
+        if self.data.config.recording_enabled {
+            let username = self.username.clone().unwrap_or_else(|| "anon".to_string());
+            let dir = std::path::Path::new(&self.data.config.recordings_dir);
+            match recorder::Recorder::start(dir, &username, 80, 24) {
+                Ok(rec) => self.recorder = Some(Arc::new(rec)),
+                Err(e) => eprintln!("Failed to start session recording: {}", e),
+            }
+        }
+
         {
-            let mut clients = self.clients.lock().await;
+            let mut clients = self.data.clients.lock().await;
             clients.insert(
                 self.id,
-                (
-                    self.username.clone().unwrap_or_default(),
-                    channel.id(),
-                    session.handle(),
-                ),
+                hub::RegisteredClient {
+                    username: self.username.clone().unwrap_or_default(),
+                    sink: hub::ClientSink::Ssh {
+                        channel: channel.id(),
+                        handle: session.handle(),
+                        recorder: self.recorder.clone(),
+                    },
+                },
             );
         }
+        {
+            let mut registry = self.data.rooms.lock().await;
+            rooms::move_client(&mut registry, self.id, None, &self.room);
+        }
 
         // Simple TUI welcome screen
-        session.data(channel.id(), CryptoVec::from("\x1b[2J\x1b[H"))?;
+        self.write_and_record(channel.id(), session, "\x1b[2J\x1b[H")?;
         if let Some(name) = &self.username {
-            let welcome = format!("Welcome, {}! Type /help for commands.\r\n", name);
-            session.data(channel.id(), CryptoVec::from(welcome))?;
+            let welcome = format!(
+                "Welcome, {}! You are in #{}. Type /help for commands.\r\n",
+                name, self.room
+            );
+            self.write_and_record(channel.id(), session, &welcome)?;
         }
 
-        // Send chat history
+        // Send the current room's chat history
         if let Some(name) = &self.username {
             let history = {
                 let conn = self.data.conn.lock().unwrap();
-                let mut stmt = conn
-                    .prepare(
-                        "SELECT users.username, messages.content FROM messages JOIN users ON users.id = messages.user_id ORDER BY messages.ts DESC LIMIT 20",
-                    )
-                    .unwrap();
-                let rows = stmt
-                    .query_map([], |row| {
-                        Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?))
-                    })
-                    .unwrap();
-                let mut vec = Vec::new();
-                for r in rows {
-                    vec.push(r.unwrap());
+                match history::query(&conn, &self.room, history::Direction::Latest, None, None, 20) {
+                    history::HistoryResult::Messages(rows) => rows,
+                    history::HistoryResult::Empty | history::HistoryResult::InvalidParams => Vec::new(),
                 }
-                vec
             };
 
-            for (u, c) in history {
-                let data = CryptoVec::from(format!("{}: {}\r\n", u, c));
-                session.data(channel.id(), data)?;
+            for (u, c, _ts, _id) in history {
+                self.write_and_record(channel.id(), session, &format!("{}: {}\r\n", u, c))?;
             }
 
-            let join_msg = format!("* {} joined", name);
-            self.broadcast(&join_msg).await;
-            session.data(channel.id(), CryptoVec::from("> "))?;
+            let join_msg = format!("* {} joined #{}", name, self.room);
+            hub::broadcast_system(&self.data.rooms, &self.data.clients, &self.room, &join_msg)
+                .await;
+            self.write_and_record(channel.id(), session, "> ")?;
         }
         Ok(true)
     }
@@ -197,32 +399,178 @@ impl server::Handler for SshServer {
             return Ok(());
         }
         if msg == "/help" {
-            let help = "Commands:\n/help - this help\n/quit - exit chat\n";
-            let clients = self.clients.lock().await;
-            if let Some((_, channel, handle)) = clients.get(&self.id) {
-                let _ = handle.data(*channel, CryptoVec::from(help)).await;
-                let _ = handle.data(*channel, CryptoVec::from("> ")).await;
-            }
+            self.send_to_self(
+                "Commands:\r\n\
+                 /help - this help\r\n\
+                 /join #room - switch to (creating if needed) a room\r\n\
+                 /part - return to #general\r\n\
+                 /rooms - list known rooms and their member counts\r\n\
+                 /history [n] - show the last n messages (default 20)\r\n\
+                 /history before <iso-ts> - show messages older than a timestamp\r\n\
+                 /msg <user> <text> - send a private message\r\n\
+                 /quit - exit chat",
+            )
+            .await;
             return Ok(());
         }
         if msg == "/quit" {
             return Err(russh::Error::Disconnect);
         }
+        if let Some(target) = msg.strip_prefix("/join ") {
+            let target = rooms::normalize_room_name(target);
+            if target.is_empty() {
+                self.send_to_self("Usage: /join #room").await;
+                return Ok(());
+            }
+            {
+                let conn = self.data.conn.lock().unwrap();
+                rooms::ensure_room(&conn, &target);
+            }
+            let previous = self.room.clone();
+            {
+                let mut registry = self.data.rooms.lock().await;
+                rooms::move_client(&mut registry, self.id, Some(&previous), &target);
+            }
+            self.room = target.clone();
+            let who = self.username.as_deref().unwrap_or("someone");
+            hub::broadcast_system(
+                &self.data.rooms,
+                &self.data.clients,
+                &target,
+                &format!("* {} joined #{}", who, target),
+            )
+            .await;
+            self.send_to_self(&format!("* joined #{}", target)).await;
+            return Ok(());
+        }
+        if msg == "/part" {
+            let previous = self.room.clone();
+            if previous == rooms::DEFAULT_ROOM {
+                self.send_to_self("* already in the default room").await;
+                return Ok(());
+            }
+            {
+                let mut registry = self.data.rooms.lock().await;
+                rooms::move_client(&mut registry, self.id, Some(&previous), rooms::DEFAULT_ROOM);
+            }
+            let who = self.username.as_deref().unwrap_or("someone");
+            hub::broadcast_system(
+                &self.data.rooms,
+                &self.data.clients,
+                &previous,
+                &format!("* {} left #{}", who, previous),
+            )
+            .await;
+            self.room = rooms::DEFAULT_ROOM.to_string();
+            self.send_to_self(&format!("* back in #{}", rooms::DEFAULT_ROOM))
+                .await;
+            return Ok(());
+        }
+        if msg == "/rooms" {
+            let listing = {
+                let registry = self.data.rooms.lock().await;
+                let mut names: Vec<String> = registry
+                    .iter()
+                    .map(|(name, state)| format!("#{} ({})", name, state.members.len()))
+                    .collect();
+                names.sort();
+                names.join(", ")
+            };
+            self.send_to_self(&format!("Rooms: {}", listing)).await;
+            return Ok(());
+        }
+        if msg == "/history" || msg.starts_with("/history ") {
+            let rest = msg.strip_prefix("/history").unwrap().trim();
+            let (direction, reference, limit) = if rest.is_empty() {
+                (history::Direction::Latest, None, 20)
+            } else if let Some(ts) = rest.strip_prefix("before ") {
+                (history::Direction::Before, Some(ts.trim().to_string()), 20)
+            } else if let Ok(n) = rest.parse::<i64>() {
+                (history::Direction::Latest, None, n)
+            } else {
+                self.send_to_self("Usage: /history [n] | /history before <iso-ts>")
+                    .await;
+                return Ok(());
+            };
+            let result = {
+                let conn = self.data.conn.lock().unwrap();
+                history::query(&conn, &self.room, direction, reference.as_deref(), None, limit)
+            };
+            match result {
+                history::HistoryResult::Messages(rows) => {
+                    let lines: Vec<String> = rows
+                        .into_iter()
+                        .map(|(u, c, ts, _id)| format!("[{}] {}: {}", ts, u, c))
+                        .collect();
+                    self.send_to_self(&lines.join("\r\n")).await;
+                }
+                history::HistoryResult::Empty => self.send_to_self("No messages").await,
+                history::HistoryResult::InvalidParams => {
+                    self.send_to_self("Usage: /history [n] | /history before <iso-ts>")
+                        .await
+                }
+            }
+            return Ok(());
+        }
+        if let Some(rest) = msg.strip_prefix("/msg ") {
+            let mut parts = rest.splitn(2, ' ');
+            let target = parts.next().unwrap_or("").trim().to_string();
+            let text = parts.next().unwrap_or("").trim().to_string();
+            if target.is_empty() || text.is_empty() {
+                self.send_to_self("Usage: /msg <user> <text>").await;
+                return Ok(());
+            }
+            let Some(name) = self.username.clone() else {
+                return Ok(());
+            };
+            let target_exists = {
+                let conn = self.data.conn.lock().unwrap();
+                if !user_exists(&conn, &target) {
+                    false
+                } else {
+                    let dialog_id = dialogs::ensure_dialog(&conn, &name, &target);
+                    if let Ok(sender_id) = conn.query_row(
+                        "SELECT id FROM users WHERE username=?1",
+                        params![name],
+                        |row| row.get::<_, i64>(0),
+                    ) {
+                        let _ = conn.execute(
+                            "INSERT INTO dialog_messages (dialog_id, sender_id, content) VALUES (?1, ?2, ?3)",
+                            params![dialog_id, sender_id, text],
+                        );
+                    }
+                    true
+                }
+            };
+            if !target_exists {
+                self.send_to_self(&format!("* No such user: {}", target)).await;
+                return Ok(());
+            }
+            let delivered = hub::deliver_dm(&self.data.clients, &target, &name, &text).await;
+            if delivered {
+                self.send_to_self(&format!("* DM sent to {}", target)).await;
+            } else {
+                self.send_to_self(&format!("* {} is offline; message saved", target))
+                    .await;
+            }
+            return Ok(());
+        }
         if let Some(name) = &self.username {
             {
                 let conn = self.data.conn.lock().unwrap();
+                let room_id = rooms::ensure_room(&conn, &self.room);
                 let mut stmt = conn
                     .prepare("SELECT id FROM users WHERE username=?1")
                     .unwrap();
                 if let Ok(uid) = stmt.query_row(params![name], |row| row.get::<_, i64>(0)) {
                     let _ = conn.execute(
-                        "INSERT INTO messages (user_id, content) VALUES (?1, ?2)",
-                        params![uid, msg.clone()],
+                        "INSERT INTO messages (user_id, room_id, content) VALUES (?1, ?2, ?3)",
+                        params![uid, room_id, msg.clone()],
                     );
                 }
             }
-            let full = format!("{}: {}", name, msg);
-            self.broadcast(&full).await;
+            hub::broadcast_message(&self.data.rooms, &self.data.clients, &self.room, name, &msg)
+                .await;
         }
         Ok(())
     }
@@ -234,12 +582,18 @@ impl server::Handler for SshServer {
     ) -> Result<(), Self::Error> {
         session.close(channel)?;
         {
-            let mut clients = self.clients.lock().await;
+            let mut clients = self.data.clients.lock().await;
             clients.remove(&self.id);
         }
+        {
+            let mut registry = self.data.rooms.lock().await;
+            if let Some(state) = registry.get_mut(&self.room) {
+                state.members.remove(&self.id);
+            }
+        }
         if let Some(name) = &self.username {
             let leave = format!("* {} left", name);
-            self.broadcast(&leave).await;
+            hub::broadcast_system(&self.data.rooms, &self.data.clients, &self.room, &leave).await;
         }
         Ok(())
     }
@@ -249,7 +603,10 @@ impl server::Handler for SshServer {
 #[template(path = "chat.html")]
 struct ChatTemplate<'a> {
     chat_name: &'a str,
+    current_room: String,
+    rooms: Vec<String>,
     messages: Vec<ChatMessage>,
+    older_url: Option<String>,
 }
 
 #[derive(Deserialize)]
@@ -261,6 +618,46 @@ struct LoginForm {
 #[derive(Deserialize)]
 struct MessageForm {
     content: String,
+    room: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct RoomQuery {
+    room: Option<String>,
+    before: Option<String>,
+    before_id: Option<i64>,
+    limit: Option<i64>,
+}
+
+#[derive(Template)]
+#[template(path = "keys.html")]
+struct KeysTemplate<'a> {
+    chat_name: &'a str,
+    keys: Vec<(String, Option<String>)>,
+}
+
+#[derive(Deserialize)]
+struct KeyForm {
+    authorized_key: String,
+}
+
+struct DialogMessage {
+    sender: String,
+    content: String,
+    ts: String,
+}
+
+#[derive(Template)]
+#[template(path = "dm.html")]
+struct DmTemplate<'a> {
+    chat_name: &'a str,
+    peer: String,
+    messages: Vec<DialogMessage>,
+}
+
+#[derive(Deserialize)]
+struct DmForm {
+    content: String,
 }
 
 #[actix_web::main]
@@ -278,28 +675,79 @@ async fn main() -> std::io::Result<()> {
     if let Some(p) = args.ssh_port {
         config.ssh_port = p;
     }
+    if let Some(p) = args.irc_port {
+        config.irc_port = p;
+    }
     if let Some(n) = args.chat_name {
         config.chat_name = n;
     }
+    if args.record {
+        config.recording_enabled = true;
+    }
+    if let Some(dir) = args.recordings_dir {
+        config.recordings_dir = dir;
+    }
 
     println!(
-        "Starting {} on http port {} and ssh port {}",
-        config.chat_name, config.http_port, config.ssh_port
+        "Starting {} on http port {}, ssh port {} and irc port {}",
+        config.chat_name, config.http_port, config.ssh_port, config.irc_port
     );
 
     let conn = Connection::open("chat.db").expect("open db");
     conn.execute(
-        "CREATE TABLE IF NOT EXISTS users (id INTEGER PRIMARY KEY, username TEXT UNIQUE, password TEXT)",
+        "CREATE TABLE IF NOT EXISTS users (id INTEGER PRIMARY KEY, username TEXT UNIQUE, password TEXT, is_admin INTEGER DEFAULT 0)",
         [],
     ).unwrap();
+    // Migrate a pre-admin chat.db: add the column so existing installs upgrade in place.
+    let _ = conn.execute("ALTER TABLE users ADD COLUMN is_admin INTEGER DEFAULT 0", []);
     conn.execute(
-        "CREATE TABLE IF NOT EXISTS messages (id INTEGER PRIMARY KEY, user_id INTEGER, content TEXT, ts DATETIME DEFAULT CURRENT_TIMESTAMP)",
+        "CREATE TABLE IF NOT EXISTS rooms (id INTEGER PRIMARY KEY, name TEXT UNIQUE)",
+        [],
+    )
+    .unwrap();
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS messages (id INTEGER PRIMARY KEY, user_id INTEGER, room_id INTEGER, content TEXT, ts DATETIME DEFAULT CURRENT_TIMESTAMP)",
         [],
     ).unwrap();
+    // Migrate a pre-rooms chat.db: add the column and file every existing
+    // message into the default room so old history keeps working.
+    let _ = conn.execute(
+        "ALTER TABLE messages ADD COLUMN room_id INTEGER REFERENCES rooms(id)",
+        [],
+    );
+    let default_room_id = rooms::ensure_room(&conn, rooms::DEFAULT_ROOM);
+    conn.execute(
+        "UPDATE messages SET room_id=?1 WHERE room_id IS NULL",
+        params![default_room_id],
+    )
+    .unwrap();
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS user_keys (id INTEGER PRIMARY KEY, user_id INTEGER, algorithm TEXT, key_blob TEXT, comment TEXT)",
+        [],
+    )
+    .unwrap();
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS dialogs (id INTEGER PRIMARY KEY, dialog_key TEXT UNIQUE)",
+        [],
+    )
+    .unwrap();
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS dialog_messages (id INTEGER PRIMARY KEY, dialog_id INTEGER, sender_id INTEGER, content TEXT, ts DATETIME DEFAULT CURRENT_TIMESTAMP)",
+        [],
+    )
+    .unwrap();
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS sessions (token TEXT PRIMARY KEY, user_id INTEGER, created_at DATETIME DEFAULT CURRENT_TIMESTAMP)",
+        [],
+    )
+    .unwrap();
 
     let data = web::Data::new(AppState {
         conn: Mutex::new(conn),
         config: config.clone(),
+        clients: AsyncMutex::new(HashMap::new()),
+        rooms: AsyncMutex::new(HashMap::new()),
+        next_client_id: AtomicUsize::new(0),
     });
 
     // Start SSH server in background
@@ -320,15 +768,23 @@ async fn main() -> std::io::Result<()> {
         let config_arc = Arc::new(server_conf);
         let mut server = SshServer {
             data: ssh_data,
-            clients: Arc::new(AsyncMutex::new(HashMap::new())),
             id: 0,
             username: None,
+            room: rooms::DEFAULT_ROOM.to_string(),
+            recorder: None,
         };
         let _ = server
             .run_on_address(config_arc, ("0.0.0.0", ssh_port))
             .await;
     });
 
+    // Start IRC listener in background
+    let irc_data = data.clone();
+    let irc_port = config.irc_port;
+    tokio::spawn(async move {
+        irc::run(irc_data, irc_port).await;
+    });
+
     HttpServer::new(move || {
         App::new()
             .app_data(data.clone())
@@ -341,19 +797,41 @@ async fn main() -> std::io::Result<()> {
             )
             .service(web::resource("/chat").route(web::get().to(chat_page)))
             .service(web::resource("/message").route(web::post().to(post_message)))
+            .service(
+                web::resource("/dm/{user}")
+                    .route(web::get().to(dm_page))
+                    .route(web::post().to(post_dm)),
+            )
             .service(web::resource("/logout").route(web::get().to(logout)))
+            .service(
+                web::resource("/keys")
+                    .route(web::get().to(keys_page))
+                    .route(web::post().to(add_key)),
+            )
+            .service(web::resource("/recordings").route(web::get().to(recordings_index)))
+            .service(web::resource("/recordings/{id}").route(web::get().to(recordings_show)))
     })
     .bind(("0.0.0.0", config.http_port))?
     .run()
     .await
 }
 
-fn get_user_id(req: &HttpRequest) -> Option<i64> {
-    req.cookie("user_id").and_then(|c| c.value().parse().ok())
+/// Resolve the `session_token` cookie to a logged-in user id, by looking it
+/// up in the server-side `sessions` table (the cookie itself carries no
+/// identity, so it can't be forged into another user's session).
+fn get_user_id(conn: &Connection, req: &HttpRequest) -> Option<i64> {
+    let token = req.cookie("session_token")?;
+    conn.query_row(
+        "SELECT user_id FROM sessions WHERE token=?1",
+        params![token.value()],
+        |row| row.get(0),
+    )
+    .ok()
 }
 
 async fn index(req: HttpRequest, data: web::Data<AppState>) -> impl Responder {
-    if get_user_id(&req).is_some() {
+    let conn = data.conn.lock().unwrap();
+    if get_user_id(&conn, &req).is_some() {
         HttpResponse::Found()
             .append_header(("Location", "/chat"))
             .finish()
@@ -380,9 +858,16 @@ async fn register_page(data: web::Data<AppState>) -> impl Responder {
 
 async fn register(form: web::Form<LoginForm>, data: web::Data<AppState>) -> impl Responder {
     let conn = data.conn.lock().unwrap();
+    let hashed = hash_password(&form.password);
+    // The very first account registered becomes the admin, since there's no
+    // other bootstrap mechanism yet.
+    let is_first_user = conn
+        .query_row("SELECT COUNT(*) FROM users", [], |row| row.get::<_, i64>(0))
+        .unwrap_or(0)
+        == 0;
     let _ = conn.execute(
-        "INSERT INTO users (username, password) VALUES (?1, ?2)",
-        params![form.username, form.password],
+        "INSERT INTO users (username, password, is_admin) VALUES (?1, ?2, ?3)",
+        params![form.username, hashed, is_first_user as i64],
     );
     HttpResponse::Found()
         .append_header(("Location", "/"))
@@ -392,16 +877,20 @@ async fn register(form: web::Form<LoginForm>, data: web::Data<AppState>) -> impl
 async fn login(form: web::Form<LoginForm>, data: web::Data<AppState>) -> impl Responder {
     let conn = data.conn.lock().unwrap();
     let mut stmt = conn
-        .prepare("SELECT id FROM users WHERE username=?1 AND password=?2")
+        .prepare("SELECT id, password FROM users WHERE username=?1")
         .unwrap();
-    let user_id: Option<i64> = stmt
-        .query_row(params![form.username, form.password], |row| row.get(0))
+    let row: Option<(i64, String)> = stmt
+        .query_row(params![form.username], |row| Ok((row.get(0)?, row.get(1)?)))
         .ok();
+    let user_id = row.and_then(|(id, stored)| {
+        check_and_upgrade_password(&conn, id, &stored, &form.password).then_some(id)
+    });
     if let Some(id) = user_id {
         let mut resp = HttpResponse::Found()
             .append_header(("Location", "/chat"))
             .finish();
-        let cookie = Cookie::build("user_id", id.to_string()).path("/").finish();
+        let token = create_session(&conn, id);
+        let cookie = Cookie::build("session_token", token).path("/").finish();
         resp.add_cookie(&cookie).unwrap();
         resp
     } else {
@@ -411,28 +900,72 @@ async fn login(form: web::Form<LoginForm>, data: web::Data<AppState>) -> impl Re
     }
 }
 
-async fn chat_page(req: HttpRequest, data: web::Data<AppState>) -> impl Responder {
-    if get_user_id(&req).is_some() {
-        let conn = data.conn.lock().unwrap();
-        let mut stmt = conn.prepare(
-            "SELECT users.username, messages.content FROM messages JOIN users ON users.id = messages.user_id ORDER BY messages.ts DESC LIMIT 20",
-        ).unwrap();
-        let rows = stmt
-            .query_map([], |row| {
-                Ok(ChatMessage {
-                    username: row.get(0)?,
-                    content: row.get(1)?,
+fn normalized_room_or_default(room: Option<&str>) -> String {
+    let room = rooms::normalize_room_name(room.unwrap_or(rooms::DEFAULT_ROOM));
+    if room.is_empty() {
+        rooms::DEFAULT_ROOM.to_string()
+    } else {
+        room
+    }
+}
+
+async fn chat_page(
+    req: HttpRequest,
+    query: web::Query<RoomQuery>,
+    data: web::Data<AppState>,
+) -> impl Responder {
+    let conn = data.conn.lock().unwrap();
+    if get_user_id(&conn, &req).is_some() {
+        let current_room = normalized_room_or_default(query.room.as_deref());
+        rooms::ensure_room(&conn, &current_room);
+
+        let limit = query.limit.unwrap_or(20);
+        let direction = if query.before.is_some() {
+            history::Direction::Before
+        } else {
+            history::Direction::Latest
+        };
+        let messages: Vec<ChatMessage> = match history::query(
+            &conn,
+            &current_room,
+            direction,
+            query.before.as_deref(),
+            query.before_id,
+            limit,
+        ) {
+            history::HistoryResult::Messages(rows) => rows
+                .into_iter()
+                .map(|(username, content, ts, id)| ChatMessage {
+                    username,
+                    content,
+                    ts,
+                    id,
                 })
-            })
-            .unwrap();
-        let mut messages = Vec::new();
+                .collect(),
+            history::HistoryResult::Empty | history::HistoryResult::InvalidParams => Vec::new(),
+        };
+
+        let older_url = messages.last().map(|oldest| {
+            format!(
+                "/chat?room={}&before={}&before_id={}&limit={}",
+                current_room, oldest.ts, oldest.id, limit
+            )
+        });
+
+        let mut stmt = conn.prepare("SELECT name FROM rooms ORDER BY name").unwrap();
+        let rows = stmt.query_map([], |row| row.get::<_, String>(0)).unwrap();
+        let mut all_rooms = Vec::new();
         for r in rows {
-            messages.push(r.unwrap());
+            all_rooms.push(r.unwrap());
         }
+
         HttpResponse::Ok().content_type("text/html").body(
             ChatTemplate {
                 chat_name: &data.config.chat_name,
+                current_room,
+                rooms: all_rooms,
                 messages,
+                older_url,
             }
             .render()
             .unwrap(),
@@ -449,23 +982,267 @@ async fn post_message(
     form: web::Form<MessageForm>,
     data: web::Data<AppState>,
 ) -> impl Responder {
-    if let Some(user_id) = get_user_id(&req) {
+    let (room, username) = {
         let conn = data.conn.lock().unwrap();
+        let Some(user_id) = get_user_id(&conn, &req) else {
+            return HttpResponse::Found()
+                .append_header(("Location", "/chat"))
+                .finish();
+        };
+        let room = normalized_room_or_default(form.room.as_deref());
+        let room_id = rooms::ensure_room(&conn, &room);
         let _ = conn.execute(
-            "INSERT INTO messages (user_id, content) VALUES (?1, ?2)",
-            params![user_id, form.content],
+            "INSERT INTO messages (user_id, room_id, content) VALUES (?1, ?2, ?3)",
+            params![user_id, room_id, form.content],
         );
+        let username = conn
+            .query_row(
+                "SELECT username FROM users WHERE id=?1",
+                params![user_id],
+                |row| row.get::<_, String>(0),
+            )
+            .ok();
+        (room, username)
+    };
+    if let Some(username) = username {
+        hub::broadcast_message(&data.rooms, &data.clients, &room, &username, &form.content).await;
     }
     HttpResponse::Found()
-        .append_header(("Location", "/chat"))
+        .append_header(("Location", format!("/chat?room={}", room)))
         .finish()
 }
 
-async fn logout() -> impl Responder {
+/// Show the logged-in user's private dialog with `peer`, oldest first.
+async fn dm_page(
+    req: HttpRequest,
+    path: web::Path<String>,
+    data: web::Data<AppState>,
+) -> impl Responder {
+    let conn = data.conn.lock().unwrap();
+    let Some(user_id) = get_user_id(&conn, &req) else {
+        return HttpResponse::Found()
+            .append_header(("Location", "/"))
+            .finish();
+    };
+    let peer = path.into_inner();
+    if !user_exists(&conn, &peer) {
+        return HttpResponse::NotFound().body("No such user");
+    }
+    let Ok(username) = conn.query_row(
+        "SELECT username FROM users WHERE id=?1",
+        params![user_id],
+        |row| row.get::<_, String>(0),
+    ) else {
+        return HttpResponse::Found()
+            .append_header(("Location", "/"))
+            .finish();
+    };
+
+    let dialog_id = dialogs::ensure_dialog(&conn, &username, &peer);
+    let mut stmt = conn
+        .prepare(
+            "SELECT users.username, dialog_messages.content, strftime('%Y-%m-%dT%H:%M:%S', dialog_messages.ts) \
+             FROM dialog_messages \
+             JOIN users ON users.id = dialog_messages.sender_id \
+             WHERE dialog_messages.dialog_id = ?1 ORDER BY dialog_messages.ts ASC",
+        )
+        .unwrap();
+    let rows = stmt
+        .query_map(params![dialog_id], |row| {
+            Ok(DialogMessage {
+                sender: row.get(0)?,
+                content: row.get(1)?,
+                ts: row.get(2)?,
+            })
+        })
+        .unwrap();
+    let mut messages = Vec::new();
+    for r in rows {
+        messages.push(r.unwrap());
+    }
+
+    HttpResponse::Ok().content_type("text/html").body(
+        DmTemplate {
+            chat_name: &data.config.chat_name,
+            peer,
+            messages,
+        }
+        .render()
+        .unwrap(),
+    )
+}
+
+/// Post a message into the logged-in user's private dialog with `peer`,
+/// delivering it immediately if `peer` is currently connected.
+async fn post_dm(
+    req: HttpRequest,
+    path: web::Path<String>,
+    form: web::Form<DmForm>,
+    data: web::Data<AppState>,
+) -> impl Responder {
+    let peer = path.into_inner();
+    let username = {
+        let conn = data.conn.lock().unwrap();
+        let Some(user_id) = get_user_id(&conn, &req) else {
+            return HttpResponse::Found()
+                .append_header(("Location", format!("/dm/{}", peer)))
+                .finish();
+        };
+        let username: Option<String> = conn
+            .query_row(
+                "SELECT username FROM users WHERE id=?1",
+                params![user_id],
+                |row| row.get(0),
+            )
+            .ok();
+        if let Some(username) = &username {
+            if user_exists(&conn, &peer) {
+                let dialog_id = dialogs::ensure_dialog(&conn, username, &peer);
+                let _ = conn.execute(
+                    "INSERT INTO dialog_messages (dialog_id, sender_id, content) VALUES (?1, ?2, ?3)",
+                    params![dialog_id, user_id, form.content],
+                );
+            }
+        }
+        username
+    };
+    if let Some(username) = username {
+        hub::deliver_dm(&data.clients, &peer, &username, &form.content).await;
+    }
+    HttpResponse::Found()
+        .append_header(("Location", format!("/dm/{}", peer)))
+        .finish()
+}
+
+/// List recorded SSH sessions (admin-only).
+async fn recordings_index(req: HttpRequest, data: web::Data<AppState>) -> impl Responder {
+    let admin = {
+        let conn = data.conn.lock().unwrap();
+        let Some(user_id) = get_user_id(&conn, &req) else {
+            return HttpResponse::Found()
+                .append_header(("Location", "/"))
+                .finish();
+        };
+        is_admin(&conn, user_id)
+    };
+    if !admin {
+        return HttpResponse::Forbidden().body("Admins only");
+    }
+
+    let mut names = Vec::new();
+    if let Ok(entries) = std::fs::read_dir(&data.config.recordings_dir) {
+        for entry in entries.flatten() {
+            if let Some(name) = entry.file_name().to_str() {
+                names.push(name.to_string());
+            }
+        }
+    }
+    names.sort();
+
+    let items: String = names
+        .iter()
+        .map(|n| format!("<li><a href=\"/recordings/{n}\">{n}</a></li>"))
+        .collect();
+    HttpResponse::Ok()
+        .content_type("text/html")
+        .body(format!("<h1>Recordings</h1><ul>{}</ul>", items))
+}
+
+/// Stream a single recording back as its raw asciicast v2 file (admin-only).
+async fn recordings_show(
+    req: HttpRequest,
+    path: web::Path<String>,
+    data: web::Data<AppState>,
+) -> impl Responder {
+    let admin = {
+        let conn = data.conn.lock().unwrap();
+        let Some(user_id) = get_user_id(&conn, &req) else {
+            return HttpResponse::Found()
+                .append_header(("Location", "/"))
+                .finish();
+        };
+        is_admin(&conn, user_id)
+    };
+    if !admin {
+        return HttpResponse::Forbidden().body("Admins only");
+    }
+
+    let id = path.into_inner();
+    if id.contains('/') || id.contains("..") {
+        return HttpResponse::BadRequest().body("Invalid recording id");
+    }
+    let file_path = std::path::Path::new(&data.config.recordings_dir).join(&id);
+    match std::fs::read_to_string(&file_path) {
+        Ok(contents) => HttpResponse::Ok()
+            .content_type("application/x-asciicast")
+            .body(contents),
+        Err(_) => HttpResponse::NotFound().body("No such recording"),
+    }
+}
+
+/// Show the logged-in user's registered SSH public keys and a form to add another.
+async fn keys_page(req: HttpRequest, data: web::Data<AppState>) -> impl Responder {
+    let conn = data.conn.lock().unwrap();
+    let Some(user_id) = get_user_id(&conn, &req) else {
+        return HttpResponse::Found()
+            .append_header(("Location", "/"))
+            .finish();
+    };
+    let mut stmt = conn
+        .prepare("SELECT algorithm, comment FROM user_keys WHERE user_id=?1 ORDER BY id")
+        .unwrap();
+    let rows = stmt
+        .query_map(params![user_id], |row| {
+            Ok((row.get::<_, String>(0)?, row.get::<_, Option<String>>(1)?))
+        })
+        .unwrap();
+    let mut keys = Vec::new();
+    for r in rows {
+        keys.push(r.unwrap());
+    }
+    HttpResponse::Ok().content_type("text/html").body(
+        KeysTemplate {
+            chat_name: &data.config.chat_name,
+            keys,
+        }
+        .render()
+        .unwrap(),
+    )
+}
+
+/// Register a new SSH public key for the logged-in user from a pasted
+/// `authorized_keys`-format line.
+async fn add_key(
+    req: HttpRequest,
+    form: web::Form<KeyForm>,
+    data: web::Data<AppState>,
+) -> impl Responder {
+    let conn = data.conn.lock().unwrap();
+    if let Some(user_id) = get_user_id(&conn, &req) {
+        if let Some((algorithm, blob, comment)) = pubkey::parse_authorized_key(&form.authorized_key)
+        {
+            let _ = conn.execute(
+                "INSERT INTO user_keys (user_id, algorithm, key_blob, comment) VALUES (?1, ?2, ?3, ?4)",
+                params![user_id, algorithm, blob, comment],
+            );
+        }
+    }
+    HttpResponse::Found()
+        .append_header(("Location", "/keys"))
+        .finish()
+}
+
+/// Log the current user out, invalidating their session server-side so the
+/// old cookie can't be replayed after logout.
+async fn logout(req: HttpRequest, data: web::Data<AppState>) -> impl Responder {
+    if let Some(token) = req.cookie("session_token") {
+        let conn = data.conn.lock().unwrap();
+        let _ = conn.execute("DELETE FROM sessions WHERE token=?1", params![token.value()]);
+    }
     let mut resp = HttpResponse::Found()
         .append_header(("Location", "/"))
         .finish();
-    let cookie = Cookie::build("user_id", "")
+    let cookie = Cookie::build("session_token", "")
         .path("/")
         .max_age(Duration::seconds(0))
         .finish();
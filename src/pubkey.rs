@@ -0,0 +1,23 @@
+/// Parse a single `authorized_keys`-format line into its algorithm, base64
+/// blob, and optional trailing comment.
+pub fn parse_authorized_key(line: &str) -> Option<(String, String, Option<String>)> {
+    let line = line.trim();
+    if line.is_empty() || line.starts_with('#') {
+        return None;
+    }
+    let mut parts = line.split_whitespace();
+    let algorithm = parts.next()?.to_string();
+    let blob = parts.next()?.to_string();
+    let comment = parts.next().map(|s| s.to_string());
+    Some((algorithm, blob, comment))
+}
+
+/// Canonical (algorithm, base64 blob) form of an offered SSH public key,
+/// matching the columns stored in `user_keys`.
+pub fn encode_public_key(key: &russh::keys::PublicKey) -> Option<(String, String)> {
+    let encoded = key.to_openssh().ok()?;
+    let mut parts = encoded.split_whitespace();
+    let algorithm = parts.next()?.to_string();
+    let blob = parts.next()?.to_string();
+    Some((algorithm, blob))
+}
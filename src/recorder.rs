@@ -0,0 +1,62 @@
+use std::fs::File;
+use std::io::Write;
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+/// Captures an SSH session's output as an asciicast v2 recording.
+///
+/// Every byte written toward the client is appended as a timestamped `"o"`
+/// (output) event; `start` anchors the `elapsed_seconds` column of each event.
+pub struct Recorder {
+    file: Mutex<File>,
+    start: Instant,
+}
+
+impl Recorder {
+    /// Begin a new recording for `username` under `dir`, naming the file by
+    /// username and start time so concurrent sessions never collide.
+    pub fn start(dir: &Path, username: &str, width: u16, height: u16) -> std::io::Result<Self> {
+        std::fs::create_dir_all(dir)?;
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        let path = dir.join(format!("{}_{}.cast", sanitize_filename_component(username), timestamp));
+        let mut file = File::create(path)?;
+        let header = serde_json::json!({
+            "version": 2,
+            "width": width,
+            "height": height,
+            "timestamp": timestamp,
+        });
+        writeln!(file, "{}", header)?;
+        Ok(Self {
+            file: Mutex::new(file),
+            start: Instant::now(),
+        })
+    }
+
+    /// Append one `[elapsed_seconds, "o", chunk]` output event.
+    pub fn record_output(&self, chunk: &str) {
+        let elapsed = self.start.elapsed().as_secs_f64();
+        let event = serde_json::json!([elapsed, "o", chunk]);
+        let mut file = self.file.lock().unwrap();
+        let _ = writeln!(file, "{}", event);
+    }
+}
+
+/// Reduce a username to characters safe to embed in a single path component,
+/// so a malicious username (e.g. containing `/` or `..`) can't steer the
+/// recording file outside `dir`.
+fn sanitize_filename_component(raw: &str) -> String {
+    let cleaned: String = raw
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() || c == '-' || c == '_' { c } else { '_' })
+        .collect();
+    if cleaned.is_empty() {
+        "anon".to_string()
+    } else {
+        cleaned
+    }
+}
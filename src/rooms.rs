@@ -0,0 +1,46 @@
+use rusqlite::{params, Connection};
+use std::collections::{HashMap, HashSet};
+
+/// Room every client starts in and the one `/part` returns them to.
+pub const DEFAULT_ROOM: &str = "general";
+
+/// Live membership for a single room: which SSH client ids are currently joined.
+#[derive(Default)]
+pub struct RoomState {
+    pub members: HashSet<usize>,
+}
+
+pub type RoomRegistry = HashMap<String, RoomState>;
+
+/// Normalize a user-supplied room name: strip an optional leading `#` and
+/// drop any character outside `[A-Za-z0-9_-]`, so the result is always safe
+/// to embed unescaped in a query string or `Location` header (e.g.
+/// `/chat?room={}`) without corrupting it or the surrounding URL.
+pub fn normalize_room_name(name: &str) -> String {
+    name.trim()
+        .trim_start_matches('#')
+        .chars()
+        .filter(|c| c.is_ascii_alphanumeric() || *c == '-' || *c == '_')
+        .collect()
+}
+
+/// Look up a room's id, creating the row the first time it's used.
+pub fn ensure_room(conn: &Connection, name: &str) -> i64 {
+    conn.execute("INSERT OR IGNORE INTO rooms (name) VALUES (?1)", params![name])
+        .unwrap();
+    conn.query_row("SELECT id FROM rooms WHERE name=?1", params![name], |row| {
+        row.get(0)
+    })
+    .unwrap()
+}
+
+/// Move `client_id` out of whichever room it currently occupies, if any, and
+/// into `to`, creating the destination room's registry entry on first use.
+pub fn move_client(registry: &mut RoomRegistry, client_id: usize, from: Option<&str>, to: &str) {
+    if let Some(from) = from {
+        if let Some(state) = registry.get_mut(from) {
+            state.members.remove(&client_id);
+        }
+    }
+    registry.entry(to.to_string()).or_default().members.insert(client_id);
+}